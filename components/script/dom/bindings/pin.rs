@@ -9,10 +9,12 @@ use dom::bindings::root::Dom;
 use dom::bindings::trace::JSTraceable;
 use js::jsapi::JSTracer;
 use std::cell::RefCell;
-use std::collections::VecDeque;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::hash::Hash;
 use std::marker::PhantomData;
 use std::mem;
-use std::ops::Drop;
+use std::ops::{Deref, DerefMut, Drop};
 
 #[allow(unrooted_must_root)]
 #[allow_unrooted_interior]
@@ -37,10 +39,77 @@ where
         T: UntracedFrom<U>,
     {
         unsafe {
-            self.cell = Some(PinCell::new(T::untraced_from(traced)));
+            self.set_cell(T::untraced_from(traced));
             self.cell.as_mut().unwrap().pin()
         }
     }
+
+    /// Like [`Pin::pin`], but for conversions that can fail. The cell is
+    /// only registered in `PINNED_TRACEABLES` on `Ok`; on `Err` nothing
+    /// is linked into the list and no half-initialized node is left
+    /// behind.
+    pub fn try_pin<U>(&'this mut self, traced: U) -> Result<&'this T, <T as TryUntracedFrom<U>>::Error>
+    where
+        T: TryUntracedFrom<U>,
+    {
+        unsafe {
+            let untraced = T::try_untraced_from(traced)?;
+            self.set_cell(untraced);
+            Ok(self.cell.as_mut().unwrap().pin())
+        }
+    }
+
+    /// Like [`Pin::pin`], but grants `&mut T` instead of `&T` while the
+    /// `PinCell` remains linked into `PINNED_TRACEABLES`: the node is
+    /// never unlinked or moved while the returned [`PinMut`] is live, so
+    /// any traceables it mutates in become visible to `trace()`
+    /// immediately.
+    pub fn pin_mut<U>(&'this mut self, traced: U) -> PinMut<'this, T>
+    where
+        T: UntracedFrom<U>,
+    {
+        unsafe {
+            self.set_cell(T::untraced_from(traced));
+            PinMut { value: self.cell.as_mut().unwrap().pin_mut() }
+        }
+    }
+
+    unsafe fn set_cell(&mut self, untraced: T) {
+        self.cell = Some(PinCell::new(untraced));
+    }
+}
+
+/// A mutable projection into a pinned value, returned by [`Pin::pin_mut`].
+///
+/// The wrapped `PinCell` stays registered in `PINNED_TRACEABLES` for as
+/// long as this handle is live, so mutating through it (e.g. pushing
+/// another `Dom<T>` into a rooted `Vec`) is visible to the tracer without
+/// re-pinning.
+pub struct PinMut<'pin, T>
+where
+    T: JSTraceable + 'static,
+{
+    value: &'pin mut T,
+}
+
+impl<'pin, T> Deref for PinMut<'pin, T>
+where
+    T: JSTraceable + 'static,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'pin, T> DerefMut for PinMut<'pin, T>
+where
+    T: JSTraceable + 'static,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        self.value
+    }
 }
 
 pub trait UntracedDefault: 'static {
@@ -62,11 +131,35 @@ macro_rules! impl_untraceddefault_as_default {
 }
 
 impl_untraceddefault_as_default!(for<T> Vec<T>);
+impl_untraceddefault_as_default!(for<T> Option<T>);
+impl_untraceddefault_as_default!(for<K, V> HashMap<K, V>);
 
 pub trait UntracedFrom<T>: 'static {
     unsafe fn untraced_from(traced: T) -> Self;
 }
 
+/// A fallible counterpart to [`UntracedFrom`], for conversions that may
+/// need to reject their input (a weak reference that no longer
+/// upgrades, a value that must be validated before being rooted) rather
+/// than panicking or silently truncating it.
+pub trait TryUntracedFrom<T>: Sized + 'static {
+    type Error;
+
+    unsafe fn try_untraced_from(traced: T) -> Result<Self, Self::Error>;
+}
+
+impl<T, U> TryUntracedFrom<U> for T
+where
+    T: UntracedFrom<U> + 'static,
+{
+    type Error = Infallible;
+
+    #[inline]
+    unsafe fn try_untraced_from(traced: U) -> Result<Self, Infallible> {
+        Ok(T::untraced_from(traced))
+    }
+}
+
 impl<'a, T> UntracedFrom<&'a mut T> for T
 where
     T: UntracedDefault + 'static,
@@ -109,6 +202,110 @@ where
     }
 }
 
+impl<'a, T, U> UntracedFrom<Option<&'a U>> for Option<T>
+where
+    T: UntracedFrom<&'a U> + 'static,
+{
+    #[inline]
+    unsafe fn untraced_from(traced: Option<&'a U>) -> Self {
+        traced.map(|value| T::untraced_from(value))
+    }
+}
+
+impl<'a, T, U> UntracedFrom<&'a Option<U>> for Option<T>
+where
+    T: UntracedFrom<&'a U> + 'static,
+{
+    #[inline]
+    unsafe fn untraced_from(traced: &'a Option<U>) -> Self {
+        traced.as_ref().map(|value| T::untraced_from(value))
+    }
+}
+
+impl<'a, T, U> UntracedFrom<&'a Box<U>> for Box<T>
+where
+    T: UntracedFrom<&'a U> + 'static,
+{
+    #[inline]
+    unsafe fn untraced_from(traced: &'a Box<U>) -> Self {
+        Box::new(T::untraced_from(&**traced))
+    }
+}
+
+impl<'a, K, V, T> UntracedFrom<&'a HashMap<K, V>> for HashMap<K, T>
+where
+    K: Clone + Eq + Hash + 'static,
+    T: UntracedFrom<&'a V> + 'static,
+{
+    #[inline]
+    unsafe fn untraced_from(traced: &'a HashMap<K, V>) -> Self {
+        traced
+            .iter()
+            .map(|(key, value)| (key.clone(), T::untraced_from(value)))
+            .collect()
+    }
+}
+
+impl<'a, T, U, const N: usize> UntracedFrom<&'a [U; N]> for [T; N]
+where
+    T: UntracedFrom<&'a U> + 'static,
+{
+    #[inline]
+    unsafe fn untraced_from(traced: &'a [U; N]) -> Self {
+        std::array::from_fn(|index| T::untraced_from(&traced[index]))
+    }
+}
+
+macro_rules! impl_untracedfrom_tuple {
+    ($(($t:ident, $u:ident, $v:ident)),+) => {
+        impl<'a, $($t, $u),+> UntracedFrom<&'a ($($u,)+)> for ($($t,)+)
+        where
+            $($t: UntracedFrom<&'a $u> + 'static),+
+        {
+            #[inline]
+            unsafe fn untraced_from(traced: &'a ($($u,)+)) -> Self {
+                let ($($v,)+) = traced;
+                ($($t::untraced_from($v),)+)
+            }
+        }
+    };
+}
+
+impl_untracedfrom_tuple!((T1, U1, v1));
+impl_untracedfrom_tuple!((T1, U1, v1), (T2, U2, v2));
+impl_untracedfrom_tuple!((T1, U1, v1), (T2, U2, v2), (T3, U3, v3));
+
+/// Root a value on the stack in one step.
+///
+/// ```ignore
+/// stack_pin!(let rooted = value);
+/// ```
+///
+/// expands to a hidden [`Pin`] local followed by a call to [`Pin::pin`],
+/// binding the resulting `&T` to `rooted` in the enclosing scope. This
+/// keeps the `unsafe` required to construct a `Pin` out of call sites
+/// entirely: the macro expansion itself upholds the invariant that the
+/// hidden `Pin` never moves and is dropped along with the binding.
+///
+/// When type inference for the target of [`UntracedFrom`] is ambiguous,
+/// annotate the binding:
+///
+/// ```ignore
+/// stack_pin!(let rooted: Dom<Node> = node);
+/// ```
+#[macro_export]
+macro_rules! stack_pin {
+    (let $rooted:ident = $value:expr) => {
+        let mut __pin = unsafe { $crate::dom::bindings::pin::Pin::new() };
+        let $rooted = __pin.pin($value);
+    };
+    (let $rooted:ident : $ty:ty = $value:expr) => {
+        let mut __pin: $crate::dom::bindings::pin::Pin<'_, $ty> =
+            unsafe { $crate::dom::bindings::pin::Pin::new() };
+        let $rooted = __pin.pin($value);
+    };
+}
+
 pub unsafe fn initialize() {
     PINNED_TRACEABLES.with(|cell| {
         let mut cell = cell.borrow_mut();
@@ -121,10 +318,10 @@ pub unsafe fn trace(tracer: *mut JSTracer) {
     trace!("tracing stack-rooted pins");
     PINNED_TRACEABLES.with(|ref cell| {
         let cell = cell.borrow();
-        let mut head = cell.unwrap();
-        while let Some(current) = head {
-            (*current).value.trace(tracer);
-            head = (*current).prev;
+        let mut current = cell.unwrap();
+        while let Some(node) = current {
+            (*node).value.trace(tracer);
+            current = (*node).next;
         }
     });
 }
@@ -134,11 +331,18 @@ thread_local! {
         Default::default();
 }
 
+// `PinCell`s form a doubly-linked list threaded through the thread-local
+// head: `next` walks from the most recently pinned cell towards the
+// oldest, `prev` walks back towards the most recently pinned. This lets
+// `drop` unlink a cell from the middle of the list, so pins do not have
+// to be dropped in the LIFO order they were created in (e.g. one of them
+// is moved into a container whose drop order differs).
 struct PinCell<T>
 where
     T: JSTraceable + ?Sized + 'static,
 {
     prev: Option<*const PinCell<JSTraceable>>,
+    next: Option<*const PinCell<JSTraceable>>,
     value: T,
 }
 
@@ -147,18 +351,28 @@ where
     T: JSTraceable + 'static,
 {
     unsafe fn new(untraced: T) -> Self {
-        Self { prev: None, value: untraced }
+        Self { prev: None, next: None, value: untraced }
     }
 
     unsafe fn pin<'pin>(&'pin mut self) -> &'pin T {
+        self.link();
+        &self.value
+    }
+
+    unsafe fn pin_mut<'pin>(&'pin mut self) -> &'pin mut T {
+        self.link();
+        &mut self.value
+    }
+
+    unsafe fn link(&mut self) {
         let this = self as &PinCell<JSTraceable> as *const _;
         PINNED_TRACEABLES.with(|cell| {
-            self.prev = mem::replace(
-                cell.borrow_mut().as_mut().unwrap(),
-                Some(this),
-            );
+            let old_head = mem::replace(cell.borrow_mut().as_mut().unwrap(), Some(this));
+            self.next = old_head;
+            if let Some(old_head) = old_head {
+                (*(old_head as *mut PinCell<JSTraceable>)).prev = Some(this);
+            }
         });
-        &self.value
     }
 }
 
@@ -168,7 +382,69 @@ where
 {
     fn drop(&mut self) {
         PINNED_TRACEABLES.with(|cell| {
-            *cell.borrow_mut().as_mut().unwrap() = self.prev;
+            match self.prev {
+                Some(prev) => unsafe { (*(prev as *mut PinCell<JSTraceable>)).next = self.next },
+                None => *cell.borrow_mut().as_mut().unwrap() = self.next,
+            }
+            if let Some(next) = self.next {
+                unsafe { (*(next as *mut PinCell<JSTraceable>)).prev = self.prev };
+            }
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    struct Leaf {
+        id: u32,
+    }
+
+    thread_local! {
+        static VISITED: RefCell<Vec<u32>> = RefCell::new(Vec::new());
+    }
+
+    unsafe impl JSTraceable for Leaf {
+        unsafe fn trace(&self, _trc: *mut JSTracer) {
+            VISITED.with(|visited| visited.borrow_mut().push(self.id));
+        }
+    }
+
+    fn traced_order() -> Vec<u32> {
+        VISITED.with(|visited| visited.borrow_mut().clear());
+        unsafe {
+            trace(ptr::null_mut());
+        }
+        VISITED.with(|visited| visited.borrow().clone())
+    }
+
+    #[test]
+    fn drop_out_of_lifo_order_keeps_list_walkable() {
+        unsafe {
+            initialize();
+
+            let mut a = PinCell::new(Leaf { id: 1 });
+            a.link();
+            let mut b = PinCell::new(Leaf { id: 2 });
+            b.link();
+            let mut c = PinCell::new(Leaf { id: 3 });
+            c.link();
+
+            // Most-recently-pinned first.
+            assert_eq!(traced_order(), vec![3, 2, 1]);
+
+            // Drop the middle pin first, out of LIFO order: `a` and `c`
+            // must end up linked directly to each other.
+            drop(b);
+            assert_eq!(traced_order(), vec![3, 1]);
+
+            drop(c);
+            assert_eq!(traced_order(), vec![1]);
+
+            drop(a);
+            assert_eq!(traced_order(), Vec::<u32>::new());
+        }
+    }
+}
@@ -0,0 +1,58 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Derives supporting `dom::bindings::pin`.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput};
+
+mod pin_root;
+
+/// Structurally root the `#[root]` fields of a struct.
+///
+/// Given:
+///
+/// ```ignore
+/// #[derive(PinRoot)]
+/// struct ReentrantState {
+///     #[root]
+///     target: Dom<Node>,
+///     #[root]
+///     related: Vec<Dom<Node>>,
+///     generation: u32,
+/// }
+/// ```
+///
+/// this generates an `UntracedReentrantState` mirror struct holding the
+/// untraced image of each `#[root]` field (plain fields like `generation`
+/// are passed through via `Clone`), an `UntracedFrom<&ReentrantState>`
+/// impl that builds the mirror field-by-field, and a `JSTraceable` impl
+/// for the mirror that traces only the `#[root]` fields. Pinning the
+/// mirror with a single `stack_pin!` then roots every `#[root]` field it
+/// contains:
+///
+/// ```ignore
+/// stack_pin!(let rooted = &reentrant_state);
+/// ```
+///
+/// A `#[root]` field can also be reached mutably, without losing its
+/// rooting, via `Pin::pin_mut` and the generated `project` method. The
+/// target type of `UntracedFrom` must be spelled out on the `Pin`
+/// binding, same as `stack_pin!`'s type-annotated form:
+///
+/// ```ignore
+/// let mut pin: Pin<'_, UntracedReentrantState> = unsafe { Pin::new() };
+/// let mut rooted = pin.pin_mut(&reentrant_state);
+/// let projection = rooted.project();
+/// projection.related.push(Dom::from_ref(&more_related));
+/// ```
+#[proc_macro_derive(PinRoot, attributes(root))]
+pub fn derive_pin_root(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    pin_root::derive(input)
+        .unwrap_or_else(|error| error.to_compile_error())
+        .into()
+}
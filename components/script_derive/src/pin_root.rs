@@ -0,0 +1,129 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Error, Fields, Type};
+
+/// Implements `#[derive(PinRoot)]`; see the crate-level doc comment for
+/// the generated shape.
+pub fn derive(input: DeriveInput) -> Result<TokenStream, Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input,
+                    "PinRoot only supports structs with named fields",
+                ));
+            },
+        },
+        _ => {
+            return Err(Error::new_spanned(&input, "PinRoot only supports structs"));
+        },
+    };
+
+    let name = &input.ident;
+    let vis = &input.vis;
+    let mirror_name = format_ident!("Untraced{}", name);
+    let projection_name = format_ident!("{}Projection", mirror_name);
+
+    let field_names: Vec<_> = fields.iter().map(|field| field.ident.as_ref().unwrap()).collect();
+    let field_types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let is_rooted: Vec<_> = fields
+        .iter()
+        .map(|field| field.attrs.iter().any(|attr| attr.path.is_ident("root")))
+        .collect();
+
+    // Rooted (`#[root]`) fields are converted through `UntracedFrom` and
+    // traced by the mirror; plain fields are pass-through (cloned as-is)
+    // and skipped by `trace()`, so a helper struct can mix rooted
+    // `Dom<T>`/`Vec<Dom<T>>` fields with plain data like flags or counts.
+    let field_conversions: Vec<_> = field_names
+        .iter()
+        .zip(&field_types)
+        .zip(&is_rooted)
+        .map(|((name, ty), &rooted)| {
+            if rooted {
+                let conversion = conversion_expr(name, ty);
+                quote! { ::dom::bindings::pin::UntracedFrom::untraced_from(#conversion) }
+            } else {
+                quote! { ::std::clone::Clone::clone(&traced.#name) }
+            }
+        })
+        .collect();
+    let traced_field_names: Vec<_> = field_names
+        .iter()
+        .zip(&is_rooted)
+        .filter(|(_, &rooted)| rooted)
+        .map(|(name, _)| *name)
+        .collect();
+    let plain_field_types: Vec<_> = field_types
+        .iter()
+        .zip(&is_rooted)
+        .filter(|(_, &rooted)| !rooted)
+        .map(|(ty, _)| *ty)
+        .collect();
+
+    Ok(quote! {
+        #[allow(unrooted_must_root)]
+        #[allow_unrooted_interior]
+        #vis struct #mirror_name {
+            #(#vis #field_names: #field_types,)*
+        }
+
+        impl<'a> ::dom::bindings::pin::UntracedFrom<&'a #name> for #mirror_name
+        where
+            #(#plain_field_types: ::std::clone::Clone,)*
+        {
+            #[inline]
+            unsafe fn untraced_from(traced: &'a #name) -> Self {
+                Self {
+                    #(#field_names: #field_conversions,)*
+                }
+            }
+        }
+
+        impl ::dom::bindings::trace::JSTraceable for #mirror_name {
+            unsafe fn trace(&self, trc: *mut ::js::jsapi::JSTracer) {
+                #(self.#traced_field_names.trace(trc);)*
+            }
+        }
+
+        #vis struct #projection_name<'pin> {
+            #(#vis #field_names: &'pin mut #field_types,)*
+        }
+
+        impl #mirror_name {
+            /// Structurally project a [`PinMut`](::dom::bindings::pin::PinMut)
+            /// of this mirror into `&mut` references to each field, so
+            /// a caller can mutate the rooted fields individually
+            /// without losing the whole-struct rooting.
+            #vis fn project(&mut self) -> #projection_name<'_> {
+                #projection_name {
+                    #(#field_names: &mut self.#field_names,)*
+                }
+            }
+        }
+    })
+}
+
+/// Builds the expression passed to `UntracedFrom::untraced_from` for a
+/// single `#[root]` field, matching the calling convention of the
+/// corresponding impl in `dom::bindings::pin`: a `Vec<T>` field is
+/// converted from a slice, an `Option<T>` field from an `Option<&U>`,
+/// and everything else from a plain reference to the whole field.
+fn conversion_expr(name: &syn::Ident, ty: &Type) -> TokenStream {
+    if let Type::Path(path) = ty {
+        if let Some(segment) = path.path.segments.last() {
+            if segment.ident == "Vec" {
+                return quote! { &traced.#name[..] };
+            }
+            if segment.ident == "Option" {
+                return quote! { traced.#name.as_ref() };
+            }
+        }
+    }
+    quote! { &traced.#name }
+}